@@ -17,23 +17,392 @@
 
 use crate::buffer::{Buffer, VecRingBuffer, VECTORED_MAX};
 use crate::core::http1::error::Error;
+use crate::core::http1::pool::{Pool, PoolKey};
 use crate::core::http1::util::*;
 use crate::future::{
-    select_2, AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, Select2, StdWriteWrapper, WriteHalf,
+    select_2, sleep, split, AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, Select2,
+    StdWriteWrapper, WriteHalf,
 };
 use crate::http1;
 use crate::pin;
+use alloc_stdlib::StandardAlloc;
+use brotli::CompressorWriter;
+use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState, HuffmanCode};
+use flate2::write::GzEncoder;
+use flate2::{Decompress, FlushDecompress, Status as InflateStatus};
 use std::cell::RefCell;
 use std::io::{self, Write};
 use std::mem;
 use std::pin::Pin;
 use std::str;
+use std::time::{Duration, Instant};
+
+// content-encoded bytes that have been removed from the transfer framing
+// (dechunked) but not yet run through the content decoder are held here,
+// bounded so a pathological compression ratio can't grow this without limit
+const CODED_BUF_MAX: usize = 64 * 1024;
+
+type BrotliDecoderState =
+    BrotliState<StandardAlloc<u8>, StandardAlloc<u32>, StandardAlloc<HuffmanCode>>;
+
+// the `Content-Encoding` applied to a response body, as understood by the
+// streaming decoder below. unknown/unsupported codings are treated as
+// identity, since we can't safely decode what we don't recognize
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentCoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+impl ContentCoding {
+    fn from_headers(headers: &[http1::Header<'_>]) -> Self {
+        for h in headers {
+            if h.name.eq_ignore_ascii_case("content-encoding") {
+                return match str::from_utf8(h.value).unwrap_or("").trim() {
+                    "gzip" | "x-gzip" => ContentCoding::Gzip,
+                    "deflate" => ContentCoding::Deflate,
+                    "br" => ContentCoding::Brotli,
+                    _ => ContentCoding::Identity,
+                };
+            }
+        }
+
+        ContentCoding::Identity
+    }
+}
+
+fn is_chunked(headers: &[http1::Header<'_>]) -> bool {
+    headers.iter().any(|h| {
+        h.name.eq_ignore_ascii_case("transfer-encoding")
+            && str::from_utf8(h.value)
+                .unwrap_or("")
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("chunked"))
+    })
+}
+
+// outcome of attempting to parse the trailer block out of whatever's
+// currently readable in `buf1`
+enum TrailerParse {
+    Ready(Vec<(String, Vec<u8>)>),
+    // not enough bytes buffered yet to know; the transfer framing being
+    // complete says nothing about whether the trailer block (sent over
+    // the same still-open socket) has fully arrived
+    NeedMoreData,
+}
+
+// trailer headers remaining readable in `buf1` right after the
+// terminating zero-length chunk, if any. an absent trailer block (the
+// common case: the blank line right after the last chunk) yields an
+// empty set rather than an error
+fn parse_trailers(buf1: &mut VecRingBuffer) -> Result<TrailerParse, Error> {
+    let src = Buffer::read_buf(buf1);
+
+    if src.is_empty() || src == b"\r\n" {
+        buf1.read_commit(src.len());
+
+        return Ok(TrailerParse::Ready(Vec::new()));
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; HEADERS_MAX];
+
+    match httparse::parse_headers(src, &mut headers) {
+        Ok(httparse::Status::Complete((n, parsed))) => {
+            let trailers = parsed
+                .iter()
+                .map(|h| (h.name.to_string(), h.value.to_vec()))
+                .collect();
+
+            buf1.read_commit(n);
+
+            Ok(TrailerParse::Ready(trailers))
+        }
+        // the trailer block didn't fully fit in what's buffered. only a
+        // hard error once the buffer is at capacity and so can never fit
+        // it; otherwise the caller can pull more bytes and retry
+        Ok(httparse::Status::Partial) => {
+            if src.len() >= buf1.capacity() {
+                Err(Error::BufferExceeded)
+            } else {
+                Ok(TrailerParse::NeedMoreData)
+            }
+        }
+        Err(_) => Err(Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed trailer",
+        ))),
+    }
+}
+
+// the request-body `Content-Encoding` a caller may opt into via
+// `Request::prepare_header`. whether it actually gets applied also
+// depends on the caller-provided content type passing `is_compressible`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Brotli,
+}
+
+// mirrors the content-compressibility tables used elsewhere to decide
+// whether pre-compressing a body is worth the CPU: text-ish and a small
+// set of structured formats are compressible, media/archives are not
+fn is_compressible(content_type: &str) -> bool {
+    let ct = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    if ct.starts_with("text/") {
+        return true;
+    }
+
+    matches!(
+        ct.as_str(),
+        "application/json"
+            | "application/javascript"
+            | "application/ecmascript"
+            | "application/xml"
+            | "application/xhtml+xml"
+            | "image/svg+xml"
+    )
+}
+
+// source bytes already compressed but not yet drained into the caller's
+// ring buffer are bounded the same way decode's staging buffer is
+const ENCODED_PENDING_MAX: usize = 64 * 1024;
+
+enum EncoderInner {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+}
+
+// streaming request-body encoder. compressed bytes are staged in an
+// owned buffer and drained into the caller's ring buffer as room allows,
+// so a slow-draining peer applies back-pressure on how much source data
+// we're willing to accept rather than growing the stage without bound
+struct Encoder {
+    inner: EncoderInner,
+}
+
+impl Encoder {
+    fn new(coding: Compression) -> Self {
+        let inner = match coding {
+            Compression::Gzip => {
+                EncoderInner::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            Compression::Brotli => {
+                EncoderInner::Brotli(Box::new(CompressorWriter::new(Vec::new(), 4096, 9, 22)))
+            }
+        };
+
+        Self { inner }
+    }
+
+    fn header_value(&self) -> &'static str {
+        match self.inner {
+            EncoderInner::Gzip(_) => "gzip",
+            EncoderInner::Brotli(_) => "br",
+        }
+    }
+
+    fn staged(&self) -> &Vec<u8> {
+        match &self.inner {
+            EncoderInner::Gzip(e) => e.get_ref(),
+            EncoderInner::Brotli(e) => e.get_ref(),
+        }
+    }
+
+    fn staged_mut(&mut self) -> &mut Vec<u8> {
+        match &mut self.inner {
+            EncoderInner::Gzip(e) => e.get_mut(),
+            EncoderInner::Brotli(e) => e.get_mut(),
+        }
+    }
+
+    // compress a prefix of `src`, bounded by the pending-output budget.
+    // returns how many source bytes were consumed
+    fn encode(&mut self, src: &[u8]) -> Result<usize, Error> {
+        if self.staged().len() >= ENCODED_PENDING_MAX {
+            return Ok(0);
+        }
+
+        let n = std::cmp::min(src.len(), ENCODED_PENDING_MAX - self.staged().len());
+
+        let result = match &mut self.inner {
+            EncoderInner::Gzip(e) => e.write_all(&src[..n]),
+            EncoderInner::Brotli(e) => e.write_all(&src[..n]),
+        };
+
+        result.map_err(Error::from)?;
+
+        Ok(n)
+    }
+
+    // flush whatever trailing bytes the format needs (gzip trailer,
+    // final brotli block)
+    fn finish(&mut self) -> Result<(), Error> {
+        match &mut self.inner {
+            EncoderInner::Gzip(e) => e.try_finish().map_err(Error::from),
+            EncoderInner::Brotli(e) => {
+                // `Write::flush` must leave the stream open for further
+                // writes (that's its contract), so it can't be what emits
+                // brotli's final (is_last) metablock; only consuming the
+                // writer does that. swap in a placeholder, finish the real
+                // one, then seed a new placeholder with the finished bytes
+                // so staged()/staged_mut()/drain_into keep working exactly
+                // as before
+                let old = mem::replace(e, Box::new(CompressorWriter::new(Vec::new(), 4096, 9, 22)));
+
+                let finished = old.into_inner();
+
+                *e = Box::new(CompressorWriter::new(finished, 4096, 9, 22));
+
+                Ok(())
+            }
+        }
+    }
+
+    // drain as much staged output as fits into `buf`
+    fn drain_into(&mut self, buf: &mut VecRingBuffer) -> Result<(), Error> {
+        let staged = self.staged_mut();
+
+        let n = match buf.write(staged) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WriteZero => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        staged.drain(..n);
+
+        Ok(())
+    }
+}
+
+// incremental content decoder. each variant owns whatever state its
+// format needs to resume across calls, since try_recv is pull-based and
+// a single call may see only part of a compressed frame
+enum Decoder {
+    Identity,
+    Gzip(Decompress),
+    Deflate(Decompress),
+    Brotli(Box<BrotliDecoderState>),
+}
+
+impl Decoder {
+    fn new(coding: ContentCoding) -> Self {
+        match coding {
+            ContentCoding::Identity => Decoder::Identity,
+            ContentCoding::Gzip => Decoder::Gzip(Decompress::new(false)),
+            ContentCoding::Deflate => Decoder::Deflate(Decompress::new(true)),
+            ContentCoding::Brotli => Decoder::Brotli(Box::new(BrotliDecoderState::new(
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+            ))),
+        }
+    }
+
+    fn is_identity(&self) -> bool {
+        matches!(self, Decoder::Identity)
+    }
+
+    // feed as much of `src` as will fit into `dest` once decompressed.
+    // returns (consumed, written). `finish` indicates no more source
+    // bytes will ever arrive, so the decoder should flush its tail
+    fn decode(
+        &mut self,
+        src: &[u8],
+        dest: &mut [u8],
+        finish: bool,
+    ) -> Result<(usize, usize), Error> {
+        match self {
+            Decoder::Gzip(d) | Decoder::Deflate(d) => {
+                let before_in = d.total_in();
+                let before_out = d.total_out();
+
+                let flush = if finish {
+                    FlushDecompress::Finish
+                } else {
+                    FlushDecompress::None
+                };
+
+                let status = d
+                    .decompress(src, dest, flush)
+                    .map_err(|e| Error::from(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+
+                let consumed = (d.total_in() - before_in) as usize;
+                let written = (d.total_out() - before_out) as usize;
+
+                // if we were asked to flush the tail and made no progress,
+                // the stream had better have actually ended; otherwise the
+                // source was truncated (e.g. connection closed mid-stream
+                // or a malformed trailer) and it will never complete
+                if finish && consumed == 0 && written == 0 && status != InflateStatus::StreamEnd {
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "truncated compressed body",
+                    )));
+                }
+
+                Ok((consumed, written))
+            }
+            Decoder::Brotli(state) => {
+                let mut available_in = src.len();
+                let mut input_offset = 0;
+                let mut available_out = dest.len();
+                let mut output_offset = 0;
+                let mut total_out = 0;
+
+                let result = BrotliDecompressStream(
+                    &mut available_in,
+                    &mut input_offset,
+                    src,
+                    &mut available_out,
+                    &mut output_offset,
+                    dest,
+                    &mut total_out,
+                    state,
+                );
+
+                if let BrotliResult::ResultFailure = result {
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "brotli decode error",
+                    )));
+                }
+
+                // same truncation check as the gzip/deflate arm: asked to
+                // flush the tail, made no progress, but the decoder never
+                // reported the stream as actually finished
+                if finish
+                    && input_offset == 0
+                    && output_offset == 0
+                    && result != BrotliResult::ResultSuccess
+                {
+                    return Err(Error::from(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "truncated compressed body",
+                    )));
+                }
+
+                Ok((input_offset, output_offset))
+            }
+            Decoder::Identity => unreachable!("identity coding is never decoded"),
+        }
+    }
+}
 
 pub struct Request<'a, R: AsyncRead, W: AsyncWrite> {
     r: ReadHalf<'a, R>,
     w: WriteHalf<'a, W>,
     buf1: &'a mut VecRingBuffer,
     buf2: &'a mut VecRingBuffer,
+    header_deadline: Option<Instant>,
+    body_deadline: Option<Instant>,
 }
 
 impl<'a, R: AsyncRead, W: AsyncWrite> Request<'a, R, W> {
@@ -47,9 +416,29 @@ impl<'a, R: AsyncRead, W: AsyncWrite> Request<'a, R, W> {
             w: stream.1,
             buf1,
             buf2,
+            header_deadline: None,
+            body_deadline: None,
         }
     }
 
+    /// Bounds how long the exchange may take, as two independent phases:
+    /// `header_deadline` covers sending the request header and receiving
+    /// the response header, while `body_deadline` covers streaming the
+    /// request and response bodies, including any time spent waiting on
+    /// a requested `Expect: 100-continue` interim response. Either may be
+    /// `None` to leave that phase unbounded. Expiry is surfaced as
+    /// `Error::Timeout` and cancels the in-flight read/write.
+    pub fn with_deadlines(
+        mut self,
+        header_deadline: Option<Instant>,
+        body_deadline: Option<Instant>,
+    ) -> Self {
+        self.header_deadline = header_deadline;
+        self.body_deadline = body_deadline;
+
+        self
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn prepare_header(
         self,
@@ -60,15 +449,51 @@ impl<'a, R: AsyncRead, W: AsyncWrite> Request<'a, R, W> {
         websocket: bool,
         initial_body: &[u8],
         end: bool,
+        compression: Option<Compression>,
+        content_type: Option<&str>,
+        expect_continue: bool,
+        continue_timeout: Duration,
     ) -> Result<RequestHeader<'a, R, W>, Error> {
+        let encoder = compression
+            .filter(|_| content_type.map_or(false, is_compressible))
+            .map(Encoder::new);
+
+        let chunked = encoder.is_some() || matches!(body_size, http1::BodySize::Chunked);
+
         let req = http1::ClientRequest::new();
 
         let size_limit = self.buf1.capacity();
 
-        let req_body = match req.send_header(self.buf1, method, uri, headers, body_size, websocket)
-        {
-            Ok(ret) => ret,
-            Err(_) => return Err(Error::RequestTooLarge(size_limit)),
+        let req_body = if let Some(encoder) = &encoder {
+            // the compressed length isn't known up front, so the body
+            // must be sent chunked regardless of what the caller asked for
+            let mut owned_headers: Vec<http1::Header> = headers
+                .iter()
+                .filter(|h| !h.name.eq_ignore_ascii_case("content-encoding"))
+                .cloned()
+                .collect();
+
+            owned_headers.push(http1::Header {
+                name: "Content-Encoding",
+                value: encoder.header_value().as_bytes(),
+            });
+
+            match req.send_header(
+                self.buf1,
+                method,
+                uri,
+                &owned_headers,
+                http1::BodySize::Chunked,
+                websocket,
+            ) {
+                Ok(ret) => ret,
+                Err(_) => return Err(Error::RequestTooLarge(size_limit)),
+            }
+        } else {
+            match req.send_header(self.buf1, method, uri, headers, body_size, websocket) {
+                Ok(ret) => ret,
+                Err(_) => return Err(Error::RequestTooLarge(size_limit)),
+            }
         };
 
         if self.buf2.write_all(initial_body).is_err() {
@@ -82,10 +507,43 @@ impl<'a, R: AsyncRead, W: AsyncWrite> Request<'a, R, W> {
             buf2: self.buf2,
             req_body,
             end,
+            encoder,
+            expect_continue,
+            continue_timeout,
+            chunked,
+            header_deadline: self.header_deadline,
+            body_deadline: self.body_deadline,
         })
     }
 }
 
+impl<'a, C: AsyncRead + AsyncWrite> Request<'a, C, C> {
+    /// Like `new`, but first tries to reclaim an idle, persistent
+    /// connection (and its buffers) from `pool` for `key`, instead of
+    /// requiring the caller to dial and allocate fresh ones. The
+    /// reclaimed connection is stored in `conn`, which must outlive the
+    /// returned `Request`. Returns `None` when nothing idle is pooled
+    /// for `key`, in which case the caller should dial a fresh
+    /// connection and fall back to `Request::new`.
+    pub fn from_pool(
+        pool: &Pool<C>,
+        key: &PoolKey,
+        conn: &'a mut Option<C>,
+        buf1: &'a mut VecRingBuffer,
+        buf2: &'a mut VecRingBuffer,
+    ) -> Option<Self> {
+        let (pooled_conn, pooled_buf1, pooled_buf2) = pool.checkout(key)?;
+
+        *conn = Some(pooled_conn);
+        *buf1 = pooled_buf1;
+        *buf2 = pooled_buf2;
+
+        let (r, w) = split(conn.as_mut().unwrap());
+
+        Some(Self::new((r, w), buf1, buf2))
+    }
+}
+
 pub struct RequestHeader<'a, R: AsyncRead, W: AsyncWrite> {
     r: ReadHalf<'a, R>,
     w: WriteHalf<'a, W>,
@@ -93,13 +551,42 @@ pub struct RequestHeader<'a, R: AsyncRead, W: AsyncWrite> {
     buf2: &'a mut VecRingBuffer,
     req_body: http1::ClientRequestBody,
     end: bool,
+    encoder: Option<Encoder>,
+    expect_continue: bool,
+    continue_timeout: Duration,
+    chunked: bool,
+    header_deadline: Option<Instant>,
+    body_deadline: Option<Instant>,
 }
 
 impl<'a, R: AsyncRead, W: AsyncWrite> RequestHeader<'a, R, W> {
     pub async fn send(mut self) -> Result<RequestBody<'a, R, W>, Error> {
         while self.buf1.len() > 0 {
-            let size = self.w.write(Buffer::read_buf(self.buf1)).await?;
-            self.buf1.read_commit(size);
+            match self.header_deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+
+                    let result = select_2(
+                        pin!(async { self.w.write(Buffer::read_buf(self.buf1)).await }),
+                        pin!(sleep(remaining)),
+                    )
+                    .await;
+
+                    match result {
+                        Select2::R1(Ok(size)) => self.buf1.read_commit(size),
+                        Select2::R1(Err(e)) => return Err(e.into()),
+                        Select2::R2(()) => {
+                            self.w.cancel();
+
+                            return Err(Error::Timeout);
+                        }
+                    }
+                }
+                None => {
+                    let size = self.w.write(Buffer::read_buf(self.buf1)).await?;
+                    self.buf1.read_commit(size);
+                }
+            }
         }
 
         let block_size = self.buf2.capacity();
@@ -116,6 +603,14 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestHeader<'a, R, W> {
                     req_body: Some(self.req_body),
                     end: self.end,
                     block_size,
+                    encoder: self.encoder,
+                    awaiting_continue: self.expect_continue,
+                    continue_timeout: self.continue_timeout,
+                    chunked: self.chunked,
+                    trailers: Vec::new(),
+                    encoder_finishing: false,
+                    header_deadline: self.header_deadline,
+                    body_deadline: self.body_deadline,
                 }),
             })),
         })
@@ -133,6 +628,43 @@ struct RequestBodyWrite<'a, W: AsyncWrite> {
     req_body: Option<http1::ClientRequestBody>,
     end: bool,
     block_size: usize,
+    encoder: Option<Encoder>,
+    // true while an `Expect: 100-continue` request is still waiting on
+    // the interim response before it may stream its body
+    awaiting_continue: bool,
+    continue_timeout: Duration,
+    // true when the body is being sent with `Transfer-Encoding: chunked`,
+    // the only framing that allows a trailer block after the final chunk
+    chunked: bool,
+    trailers: Vec<(String, Vec<u8>)>,
+    // true once `encoder.finish()` has been called but its staged output
+    // (e.g. the gzip trailer) didn't fully fit into `buf` yet. kept separate
+    // from `end` so the terminating chunk isn't sent until the encoder is
+    // truly drained
+    encoder_finishing: bool,
+    // carried through to the `Response` once the body phase completes, so
+    // `Response::recv_header` can keep honoring the header-phase deadline
+    header_deadline: Option<Instant>,
+    body_deadline: Option<Instant>,
+}
+
+impl<'a, W: AsyncWrite> RequestBodyWrite<'a, W> {
+    // keep draining a finished encoder's staged tail into `buf` across
+    // calls, since a single drain may not have had room for all of it
+    fn pump_encoder(&mut self) -> Result<(), Error> {
+        if self.encoder_finishing {
+            let encoder = self.encoder.as_mut().unwrap();
+
+            encoder.drain_into(self.buf)?;
+
+            if encoder.staged().is_empty() {
+                self.encoder_finishing = false;
+                self.end = true;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 struct RequestBodyInner<'a, R: AsyncRead, W: AsyncWrite> {
@@ -154,6 +686,26 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
                 return Err(Error::FurtherInputNotAllowed);
             }
 
+            if let Some(encoder) = &mut w.encoder {
+                let consumed = encoder.encode(src)?;
+                encoder.drain_into(w.buf)?;
+
+                if consumed == src.len() && end {
+                    encoder.finish()?;
+                    encoder.drain_into(w.buf)?;
+
+                    if encoder.staged().is_empty() {
+                        w.end = true;
+                    } else {
+                        // trailer didn't fully fit; keep pumping it on
+                        // subsequent can_send/process calls until it does
+                        w.encoder_finishing = true;
+                    }
+                }
+
+                return Ok(consumed);
+            }
+
             let size = match w.buf.write(src) {
                 Ok(size) => size,
                 Err(e) if e.kind() == io::ErrorKind::WriteZero => 0,
@@ -172,6 +724,28 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
         }
     }
 
+    // Sets trailer headers to be sent after the terminating chunk. Only
+    // meaningful when the body is being sent as `Transfer-Encoding: chunked`;
+    // any other framing has no way to carry a trailer block.
+    pub fn set_trailers(&self, trailers: &[(&str, &[u8])]) -> Result<(), Error> {
+        if let Some(inner) = &*self.inner.borrow() {
+            let w = &mut *inner.w.borrow_mut();
+
+            if !w.chunked {
+                return Err(Error::FurtherInputNotAllowed);
+            }
+
+            w.trailers = trailers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_vec()))
+                .collect();
+
+            Ok(())
+        } else {
+            Err(Error::Unusable)
+        }
+    }
+
     pub fn expand_write_buffer<F>(&self, blocks_max: usize, reserve: F) -> Result<usize, Error>
     where
         F: Fn() -> bool,
@@ -192,7 +766,11 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
 
     pub fn can_send(&self) -> bool {
         if let Some(inner) = &*self.inner.borrow() {
-            let w = &*inner.w.borrow();
+            let w = &mut *inner.w.borrow_mut();
+
+            // best-effort: a real drain failure will surface from
+            // process() instead, which can actually report an Error
+            let _ = w.pump_encoder();
 
             w.buf.len() > 0 || w.end
         } else {
@@ -205,6 +783,10 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
             return SendStatus::Error((), Error::Unusable);
         }
 
+        if let Err(e) = self.wait_for_continue().await {
+            return SendStatus::Error((), e);
+        }
+
         let status = loop {
             if let Some(inner) = self.take_inner_if_early_response() {
                 let r = inner.r.into_inner();
@@ -218,6 +800,8 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
                     buf1: r.buf,
                     buf2: w.buf,
                     inner: resp,
+                    header_deadline: w.header_deadline,
+                    body_deadline: w.body_deadline,
                 });
             }
 
@@ -242,11 +826,16 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
 
                 assert_eq!(w.buf.len(), 0);
 
+                let header_deadline = w.header_deadline;
+                let body_deadline = w.body_deadline;
+
                 SendStatus::Complete(Response {
                     r: r.stream,
                     buf1: r.buf,
                     buf2: w.buf,
                     inner: resp,
+                    header_deadline,
+                    body_deadline,
                 })
             }
             http1::SendStatus::Partial(req_body, size) => {
@@ -282,9 +871,15 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
         let inner = self.inner.borrow();
         let inner = inner.as_ref().unwrap();
 
+        if let Err(e) = inner.w.borrow_mut().pump_encoder() {
+            return Some(Err(e));
+        }
+
+        let body_deadline = inner.w.borrow().body_deadline;
+
         let mut r = inner.r.borrow_mut();
 
-        let result = select_2(
+        let combined = select_2(
             AsyncOperation::new(
                 |cx| {
                     let w = &mut *inner.w.borrow_mut();
@@ -301,11 +896,23 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
                     let mut buf_arr = [&b""[..]; VECTORED_MAX - 2];
                     let bufs = w.buf.read_bufs(&mut buf_arr);
 
+                    let trailer_headers: Vec<http1::Header> = w
+                        .trailers
+                        .iter()
+                        .map(|(name, value)| http1::Header { name, value })
+                        .collect();
+
+                    let trailers = if w.end && !trailer_headers.is_empty() {
+                        Some(trailer_headers.as_slice())
+                    } else {
+                        None
+                    };
+
                     match req_body.send(
                         &mut StdWriteWrapper::new(Pin::new(&mut w.stream), cx),
                         bufs,
                         w.end,
-                        None,
+                        trailers,
                     ) {
                         http1::SendStatus::Error(req_body, http1::Error::Io(e))
                             if e.kind() == io::ErrorKind::WouldBlock =>
@@ -333,8 +940,24 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
 
                 Ok(())
             }),
-        )
-        .await;
+        );
+
+        let result = match body_deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+
+                match select_2(pin!(combined), pin!(sleep(remaining))).await {
+                    Select2::R1(ret) => ret,
+                    Select2::R2(()) => {
+                        inner.w.borrow_mut().stream.cancel();
+                        r.stream.cancel();
+
+                        return Some(Err(Error::Timeout));
+                    }
+                }
+            }
+            None => combined.await,
+        };
 
         match result {
             Select2::R1(ret) => match ret {
@@ -381,6 +1004,114 @@ impl<'a, R: AsyncRead, W: AsyncWrite> RequestBody<'a, R, W> {
             None
         }
     }
+
+    // assumes self.inner is Some. no-op unless the request was prepared
+    // with `expect_continue`. on return, either a "100 Continue" interim
+    // response has been consumed from the read buffer and the body may
+    // be streamed, or any data the server did send has been left alone
+    // for `take_inner_if_early_response` to pick up as an early response
+    async fn wait_for_continue(&self) -> Result<(), Error> {
+        // the earlier of the continue-specific timeout and whatever's left
+        // of body_deadline, if any, wins; which one wins decides whether
+        // running it out is a lenient "give up waiting, send the body
+        // anyway" or a hard `Error::Timeout` on the exchange deadline
+        let (deadline, is_body_deadline) = {
+            let b_inner = self.inner.borrow();
+            let inner = b_inner.as_ref().unwrap();
+            let w = inner.w.borrow();
+
+            if !w.awaiting_continue {
+                return Ok(());
+            }
+
+            let continue_deadline = Instant::now() + w.continue_timeout;
+
+            match w.body_deadline {
+                Some(body_deadline) if body_deadline < continue_deadline => {
+                    (body_deadline, true)
+                }
+                _ => (continue_deadline, false),
+            }
+        };
+
+        loop {
+            let b_inner = self.inner.borrow();
+            let inner = b_inner.as_ref().unwrap();
+
+            {
+                let mut r = inner.r.borrow_mut();
+
+                if r.buf.len() > 0 {
+                    if let Some((code, consumed)) = peek_status_line(Buffer::read_buf(r.buf))? {
+                        if code == 100 {
+                            r.buf.read_commit(consumed);
+                        }
+
+                        drop(r);
+
+                        inner.w.borrow_mut().awaiting_continue = false;
+
+                        return Ok(());
+                    }
+                }
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                inner.w.borrow_mut().awaiting_continue = false;
+
+                if is_body_deadline {
+                    return Err(Error::Timeout);
+                }
+
+                return Ok(());
+            }
+
+            let mut r = inner.r.borrow_mut();
+            let r = &mut *r;
+
+            let result = select_2(
+                pin!(async { recv_nonzero(&mut r.stream, r.buf).await }),
+                pin!(sleep(deadline - now)),
+            )
+            .await;
+
+            match result {
+                Select2::R1(Ok(())) => {} // got more data; loop and re-check
+                Select2::R1(Err(e)) => return Err(e.into()),
+                Select2::R2(()) => {
+                    inner.w.borrow_mut().awaiting_continue = false;
+
+                    if is_body_deadline {
+                        r.stream.cancel();
+
+                        return Err(Error::Timeout);
+                    }
+
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// parses a single status line (plus header block) from the front of
+// `buf`, without consuming anything. returns the status code and the
+// number of bytes it occupies once complete, or `None` if more data is
+// needed to know
+fn peek_status_line(buf: &[u8]) -> Result<Option<(u16, usize)>, Error> {
+    let mut headers = [httparse::EMPTY_HEADER; HEADERS_MAX];
+    let mut resp = httparse::Response::new(&mut headers);
+
+    match resp.parse(buf) {
+        Ok(httparse::Status::Complete(n)) => Ok(Some((resp.code.unwrap_or(0), n))),
+        Ok(httparse::Status::Partial) => Ok(None),
+        Err(_) => Err(Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed interim response",
+        ))),
+    }
 }
 
 pub struct Response<'a, R: AsyncRead> {
@@ -388,6 +1119,8 @@ pub struct Response<'a, R: AsyncRead> {
     buf1: &'a mut VecRingBuffer,
     buf2: &'a mut VecRingBuffer,
     inner: http1::ClientResponse,
+    header_deadline: Option<Instant>,
+    body_deadline: Option<Instant>,
 }
 
 impl<'a, R: AsyncRead> Response<'a, R> {
@@ -425,18 +1158,47 @@ impl<'a, R: AsyncRead> Response<'a, R> {
                 continue;
             }
 
-            if let Err(e) = recv_nonzero(&mut self.r, self.buf1).await {
-                if e.kind() == io::ErrorKind::WriteZero {
-                    return Err(Error::BufferExceeded);
+            match self.header_deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+
+                    let result = select_2(
+                        pin!(async { recv_nonzero(&mut self.r, self.buf1).await }),
+                        pin!(sleep(remaining)),
+                    )
+                    .await;
+
+                    match result {
+                        Select2::R1(Ok(())) => {}
+                        Select2::R1(Err(e)) if e.kind() == io::ErrorKind::WriteZero => {
+                            return Err(Error::BufferExceeded)
+                        }
+                        Select2::R1(Err(e)) => return Err(e.into()),
+                        Select2::R2(()) => {
+                            self.r.cancel();
+
+                            return Err(Error::Timeout);
+                        }
+                    }
                 }
+                None => {
+                    if let Err(e) = recv_nonzero(&mut self.r, self.buf1).await {
+                        if e.kind() == io::ErrorKind::WriteZero {
+                            return Err(Error::BufferExceeded);
+                        }
 
-                return Err(e.into());
+                        return Err(e.into());
+                    }
+                }
             }
         };
 
         // at this point, resp has taken buf1's inner buffer, such that
         // buf1 has no inner buffer
 
+        let coding = ContentCoding::from_headers(resp.headers());
+        let chunked = is_chunked(resp.headers());
+
         // put remaining readable bytes in buf2
         self.buf2.write_all(resp.remaining_bytes())?;
 
@@ -453,6 +1215,12 @@ impl<'a, R: AsyncRead> Response<'a, R> {
                         closed: false,
                         buf1: self.buf1,
                         resp_body,
+                        decoder: Decoder::new(coding),
+                        coded: Vec::new(),
+                        transfer_done: false,
+                        raw_finished: None,
+                        chunked,
+                        body_deadline: self.body_deadline,
                     })),
                 },
                 buf2: RefCell::new(Some(self.buf2)),
@@ -466,6 +1234,18 @@ struct ResponseBodyInner<'a, R: AsyncRead> {
     closed: bool,
     buf1: &'a mut VecRingBuffer,
     resp_body: http1::ClientResponseBody,
+    decoder: Decoder,
+    // transfer-decoded (dechunked) bytes awaiting content decoding.
+    // empty and unused when decoder is Decoder::Identity
+    coded: Vec<u8>,
+    // true once resp_body has reported the transfer framing complete
+    transfer_done: bool,
+    // set once transfer_done, held until the decoder has flushed its tail
+    // and the trailer block (if any) has fully arrived
+    raw_finished: Option<http1::ClientFinished>,
+    // trailers are only meaningful (and only looked for) on chunked bodies
+    chunked: bool,
+    body_deadline: Option<Instant>,
 }
 
 pub struct ResponseBody<'a, R: AsyncRead> {
@@ -478,7 +1258,28 @@ impl<'a, R: AsyncRead> ResponseBody<'a, R> {
     pub async fn add_to_buffer(&self) -> Result<(), Error> {
         if let Some(inner) = &mut *self.inner.borrow_mut() {
             if !inner.closed {
-                match recv_nonzero(&mut inner.r, inner.buf1).await {
+                let result = match inner.body_deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+
+                        match select_2(
+                            pin!(async { recv_nonzero(&mut inner.r, inner.buf1).await }),
+                            pin!(sleep(remaining)),
+                        )
+                        .await
+                        {
+                            Select2::R1(ret) => ret,
+                            Select2::R2(()) => {
+                                inner.r.cancel();
+
+                                return Err(Error::Timeout);
+                            }
+                        }
+                    }
+                    None => recv_nonzero(&mut inner.r, inner.buf1).await,
+                };
+
+                match result {
                     Ok(()) => {}
                     Err(e) if e.kind() == io::ErrorKind::WriteZero => {
                         return Err(Error::BufferExceeded)
@@ -498,7 +1299,44 @@ impl<'a, R: AsyncRead> ResponseBody<'a, R> {
         loop {
             let mut b_inner = self.inner.borrow_mut();
 
-            if let Some(inner) = b_inner.take() {
+            let mut inner = match b_inner.take() {
+                Some(inner) => inner,
+                None => return Err(Error::Unusable),
+            };
+
+            if inner.decoder.is_identity() {
+                // transfer framing already finished; all that's left is
+                // to see whether the trailer block has fully arrived
+                if inner.transfer_done {
+                    match parse_trailers(inner.buf1)? {
+                        TrailerParse::Ready(trailers) => {
+                            let finished = inner.raw_finished.take().unwrap();
+
+                            break Ok(RecvStatus::Complete(
+                                Finished {
+                                    inner: finished,
+                                    trailers,
+                                },
+                                0,
+                            ));
+                        }
+                        TrailerParse::NeedMoreData => {
+                            if inner.closed {
+                                return Err(Error::from(io::Error::new(
+                                    io::ErrorKind::UnexpectedEof,
+                                    "connection closed with trailer block incomplete",
+                                )));
+                            }
+
+                            *b_inner = Some(inner);
+
+                            return Ok(RecvStatus::Read((), 0));
+                        }
+                    }
+                }
+
+                // no content decoding: transfer-decoded bytes go straight
+                // to dest, same as before this coding support existed
                 let mut scratch = mem::MaybeUninit::<[httparse::Header; HEADERS_MAX]>::uninit();
 
                 let src = Buffer::read_buf(inner.buf1);
@@ -508,32 +1346,164 @@ impl<'a, R: AsyncRead> ResponseBody<'a, R> {
                     http1::RecvStatus::Complete(finished, read, written) => {
                         inner.buf1.read_commit(read);
 
-                        *b_inner = None;
-
-                        break Ok(RecvStatus::Complete(Finished { inner: finished }, written));
+                        if inner.chunked {
+                            match parse_trailers(inner.buf1)? {
+                                TrailerParse::Ready(trailers) => {
+                                    break Ok(RecvStatus::Complete(
+                                        Finished {
+                                            inner: finished,
+                                            trailers,
+                                        },
+                                        written,
+                                    ));
+                                }
+                                TrailerParse::NeedMoreData => {
+                                    if inner.closed {
+                                        return Err(Error::from(io::Error::new(
+                                            io::ErrorKind::UnexpectedEof,
+                                            "connection closed with trailer block incomplete",
+                                        )));
+                                    }
+
+                                    inner.transfer_done = true;
+                                    inner.raw_finished = Some(finished);
+                                    *b_inner = Some(inner);
+
+                                    return Ok(RecvStatus::Read((), written));
+                                }
+                            }
+                        } else {
+                            break Ok(RecvStatus::Complete(
+                                Finished {
+                                    inner: finished,
+                                    trailers: Vec::new(),
+                                },
+                                written,
+                            ));
+                        }
                     }
                     http1::RecvStatus::Read(resp_body, read, written) => {
-                        *b_inner = Some(ResponseBodyInner {
-                            r: inner.r,
-                            closed: inner.closed,
-                            buf1: inner.buf1,
-                            resp_body,
-                        });
-
-                        let inner = b_inner.as_mut().unwrap();
+                        inner.resp_body = resp_body;
 
                         if read == 0 && written == 0 && !inner.buf1.is_readable_contiguous() {
                             inner.buf1.align();
+                            *b_inner = Some(inner);
                             continue;
                         }
 
                         inner.buf1.read_commit(read);
 
+                        *b_inner = Some(inner);
+
                         return Ok(RecvStatus::Read((), written));
                     }
                 }
             } else {
-                return Err(Error::Unusable);
+                // drain whatever content-encoded bytes are already staged
+                // before pulling more off the wire, so a decoder that's
+                // sitting on a full dest's worth of output doesn't stall
+                if !inner.coded.is_empty() || inner.transfer_done {
+                    let (consumed, written) =
+                        inner
+                            .decoder
+                            .decode(&inner.coded, dest, inner.transfer_done)?;
+
+                    inner.coded.drain(..consumed);
+
+                    if inner.transfer_done
+                        && inner.coded.is_empty()
+                        && consumed == 0
+                        && written == 0
+                    {
+                        let parsed = if inner.chunked {
+                            parse_trailers(inner.buf1)?
+                        } else {
+                            TrailerParse::Ready(Vec::new())
+                        };
+
+                        match parsed {
+                            TrailerParse::Ready(trailers) => {
+                                let finished = inner.raw_finished.take().unwrap();
+
+                                break Ok(RecvStatus::Complete(
+                                    Finished {
+                                        inner: finished,
+                                        trailers,
+                                    },
+                                    written,
+                                ));
+                            }
+                            TrailerParse::NeedMoreData => {
+                                if inner.closed {
+                                    return Err(Error::from(io::Error::new(
+                                        io::ErrorKind::UnexpectedEof,
+                                        "connection closed with trailer block incomplete",
+                                    )));
+                                }
+
+                                *b_inner = Some(inner);
+
+                                return Ok(RecvStatus::Read((), written));
+                            }
+                        }
+                    }
+
+                    if consumed > 0 || written > 0 {
+                        *b_inner = Some(inner);
+
+                        return Ok(RecvStatus::Read((), written));
+                    }
+                }
+
+                if inner.transfer_done {
+                    // transfer framing already finished and the decoder
+                    // had nothing left to flush this round
+                    *b_inner = Some(inner);
+
+                    return Ok(RecvStatus::Read((), 0));
+                }
+
+                // pull more transfer-decoded (still content-encoded)
+                // bytes off the wire into the staging buffer
+                let mut scratch = mem::MaybeUninit::<[httparse::Header; HEADERS_MAX]>::uninit();
+
+                let src = Buffer::read_buf(inner.buf1);
+                let end = src.len() == inner.buf1.len() && inner.closed;
+
+                let mut tmp = [0u8; 8192];
+
+                match inner.resp_body.recv(src, &mut tmp, end, &mut scratch)? {
+                    http1::RecvStatus::Complete(finished, read, written) => {
+                        inner.buf1.read_commit(read);
+
+                        if inner.coded.len() + written > CODED_BUF_MAX {
+                            return Err(Error::BufferExceeded);
+                        }
+
+                        inner.coded.extend_from_slice(&tmp[..written]);
+                        inner.transfer_done = true;
+                        inner.raw_finished = Some(finished);
+                    }
+                    http1::RecvStatus::Read(resp_body, read, written) => {
+                        inner.resp_body = resp_body;
+
+                        if read == 0 && written == 0 && !inner.buf1.is_readable_contiguous() {
+                            inner.buf1.align();
+                            *b_inner = Some(inner);
+                            continue;
+                        }
+
+                        inner.buf1.read_commit(read);
+
+                        if inner.coded.len() + written > CODED_BUF_MAX {
+                            return Err(Error::BufferExceeded);
+                        }
+
+                        inner.coded.extend_from_slice(&tmp[..written]);
+                    }
+                }
+
+                *b_inner = Some(inner);
             }
         }
     }
@@ -586,12 +1556,39 @@ impl<'a, R: AsyncRead> ResponseBodyKeepHeader<'a, R> {
 
 pub struct Finished {
     inner: http1::ClientFinished,
+    // empty for non-chunked bodies, or chunked bodies with no trailer
+    trailers: Vec<(String, Vec<u8>)>,
 }
 
 impl Finished {
     pub fn is_persistent(&self) -> bool {
         self.inner.persistent
     }
+
+    /// Trailer headers sent after the terminating chunk, in wire order.
+    /// Always empty for non-chunked bodies.
+    pub fn trailers(&self) -> &[(String, Vec<u8>)] {
+        &self.trailers
+    }
+
+    /// Returns `conn` and its buffers to `pool` for reuse under `key`,
+    /// if this exchange finished in a way that allows the connection to
+    /// stay open. Otherwise hands them back to the caller to close.
+    pub fn try_release<C: AsyncRead + AsyncWrite>(
+        self,
+        pool: &Pool<C>,
+        key: PoolKey,
+        conn: C,
+        buf1: VecRingBuffer,
+        buf2: VecRingBuffer,
+    ) -> Option<(C, VecRingBuffer, VecRingBuffer)> {
+        if self.is_persistent() {
+            pool.checkin(key, conn, buf1, buf2);
+            None
+        } else {
+            Some((conn, buf1, buf2))
+        }
+    }
 }
 
 pub struct FinishedKeepHeader<'a> {
@@ -610,4 +1607,24 @@ impl<'a> FinishedKeepHeader<'a> {
     pub fn is_persistent(&self) -> bool {
         self.inner.is_persistent()
     }
+
+    pub fn trailers(&self) -> &[(String, Vec<u8>)] {
+        self.inner.trailers()
+    }
+
+    /// Returns `conn` and its buffers to `pool` for reuse under `key`,
+    /// discarding the still-pending header buffer first. See
+    /// `Finished::try_release`.
+    pub fn try_release<const N: usize, C: AsyncRead + AsyncWrite>(
+        self,
+        resp: http1::OwnedResponse<N>,
+        pool: &Pool<C>,
+        key: PoolKey,
+        conn: C,
+        buf1: VecRingBuffer,
+        buf2: VecRingBuffer,
+    ) -> Option<(C, VecRingBuffer, VecRingBuffer)> {
+        self.discard_header(resp)
+            .try_release(pool, key, conn, buf1, buf2)
+    }
 }
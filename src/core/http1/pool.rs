@@ -0,0 +1,122 @@
+/*
+ * Copyright (C) 2020-2023 Fanout, Inc.
+ * Copyright (C) 2023-2024 Fastly, Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::buffer::VecRingBuffer;
+use crate::future::{AsyncRead, AsyncWrite};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Identifies the origin a persistent connection can be reused for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub scheme: &'static str,
+    pub host: String,
+    pub port: u16,
+}
+
+impl PoolKey {
+    pub fn new(scheme: &'static str, host: &str, port: u16) -> Self {
+        Self {
+            scheme,
+            host: host.to_string(),
+            port,
+        }
+    }
+}
+
+struct Idle<C> {
+    conn: C,
+    buf1: VecRingBuffer,
+    buf2: VecRingBuffer,
+    since: Instant,
+}
+
+/// Holds connections reclaimed from finished, persistent exchanges so a
+/// later request to the same (scheme, host, port) can skip dialing and
+/// handshaking a new one. Idle connections older than `idle_timeout` are
+/// evicted lazily, on the next checkout/checkin for their key.
+pub struct Pool<C> {
+    max_idle_per_key: usize,
+    idle_timeout: Duration,
+    idle: RefCell<HashMap<PoolKey, Vec<Idle<C>>>>,
+}
+
+impl<C: AsyncRead + AsyncWrite> Pool<C> {
+    pub fn new(max_idle_per_key: usize, idle_timeout: Duration) -> Self {
+        Self {
+            max_idle_per_key,
+            idle_timeout,
+            idle: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Reclaims an idle connection for `key`, if one is available and
+    /// hasn't aged out, along with the read/write buffers it was last
+    /// using (already allocated, so the caller avoids a fresh alloc).
+    pub fn checkout(&self, key: &PoolKey) -> Option<(C, VecRingBuffer, VecRingBuffer)> {
+        let mut idle = self.idle.borrow_mut();
+
+        let entries = idle.get_mut(key)?;
+
+        let now = Instant::now();
+        entries.retain(|e| now.duration_since(e.since) < self.idle_timeout);
+
+        if entries.is_empty() {
+            idle.remove(key);
+            return None;
+        }
+
+        let entry = entries.pop()?;
+
+        if entries.is_empty() {
+            idle.remove(key);
+        }
+
+        Some((entry.conn, entry.buf1, entry.buf2))
+    }
+
+    /// Returns a connection and its buffers to the pool for `key`, once
+    /// the exchange that used them has finished persistently. Buffers
+    /// are cleared so the next checkout starts from an empty state. If
+    /// the per-key idle cap is already full, the connection is dropped
+    /// (and, with it, closed) rather than displacing an older entry.
+    pub fn checkin(&self, key: PoolKey, conn: C, mut buf1: VecRingBuffer, mut buf2: VecRingBuffer) {
+        buf1.clear();
+        buf2.clear();
+
+        let mut idle = self.idle.borrow_mut();
+        let entries = idle.entry(key).or_insert_with(Vec::new);
+
+        let now = Instant::now();
+        entries.retain(|e| now.duration_since(e.since) < self.idle_timeout);
+
+        if entries.len() < self.max_idle_per_key {
+            entries.push(Idle {
+                conn,
+                buf1,
+                buf2,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Number of idle connections currently pooled for `key`.
+    pub fn idle_count(&self, key: &PoolKey) -> usize {
+        self.idle.borrow().get(key).map(Vec::len).unwrap_or(0)
+    }
+}